@@ -0,0 +1,145 @@
+//! An alternative to the `docker compose` subprocess polling used by [`crate::DockerCompose`].
+//!
+//! Instead of re-invoking `docker compose logs` on every poll iteration, this backend talks to
+//! the Docker daemon directly via [`bollard`] and attaches to each container's log stream once,
+//! feeding newly arrived lines to the readiness regexes as they come in. Services that declare an
+//! enabled `healthcheck` are instead polled via the daemon's own container health status.
+//!
+//! Only available when the `bollard` feature is enabled; environments without access to the
+//! Docker API socket should keep using the subprocess path in [`crate`].
+
+use crate::{Readiness, Service, compose_file_args, run_command};
+use bollard::Docker;
+use bollard::container::{InspectContainerOptions, LogsOptions};
+use bollard::models::HealthStatusEnum;
+use futures_util::stream::StreamExt;
+use std::time::{self, Duration};
+use tracing::trace;
+
+/// Wait until the requirements in every Service is met, by streaming container logs (or polling
+/// container health) through the Docker API instead of repeatedly shelling out to
+/// `docker compose logs`.
+/// Will panic if a timeout occurs.
+pub(crate) fn wait_for_logs(compose_files: &[String], services: &mut [&mut Service]) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    runtime.block_on(wait_for_logs_async(compose_files, services));
+}
+
+async fn wait_for_logs_async(compose_files: &[String], services: &mut [&mut Service]) {
+    let docker = Docker::connect_with_local_defaults().unwrap();
+
+    let timeout = services
+        .iter()
+        .map(|service| service.timeout)
+        .max_by_key(|x| x.as_nanos())
+        .unwrap();
+
+    // A container's log history survives a `stop`/`start` restart, so without `since` a
+    // restarted container's stream would immediately replay an old readiness line. Scope every
+    // stream to lines emitted after this particular wait started, mirroring the subprocess
+    // backend's `--since` flag.
+    let since = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64;
+
+    let mut container_ids = Vec::with_capacity(services.len());
+    for service in services.iter() {
+        let container_id = {
+            let mut args = compose_file_args(compose_files);
+            args.extend(["ps", "-q", &service.name]);
+            run_command("docker", &args).unwrap().trim().to_string()
+        };
+        container_ids.push(container_id);
+    }
+
+    // Only services waiting on a log regex need a log stream; healthcheck services are polled
+    // via `inspect_container` below instead.
+    let mut streams: Vec<_> = services
+        .iter()
+        .zip(&container_ids)
+        .map(|(service, container_id)| match &service.readiness {
+            Readiness::LogRegex(_) => Some(docker.logs(
+                container_id,
+                Some(LogsOptions::<String> {
+                    follow: true,
+                    stdout: true,
+                    stderr: true,
+                    since,
+                    ..Default::default()
+                }),
+            )),
+            Readiness::Healthcheck => None,
+        })
+        .collect();
+
+    let instant = time::Instant::now();
+    let mut ready = vec![false; services.len()];
+    loop {
+        for (i, service) in services.iter().enumerate() {
+            if ready[i] {
+                continue;
+            }
+
+            match (&service.readiness, &mut streams[i]) {
+                (Readiness::LogRegex(regex), Some(stream)) => {
+                    // Drain whatever is currently buffered on this container's log stream and
+                    // test each newly arrived line against the readiness regex, rather than
+                    // rescanning everything seen so far.
+                    while let Ok(Some(Ok(chunk))) =
+                        tokio::time::timeout(Duration::from_millis(50), stream.next()).await
+                    {
+                        let line = chunk.to_string();
+                        if regex.is_match(&line) {
+                            ready[i] = true;
+                            trace!("service {} ready after matching line: {}", service.name, line);
+                            break;
+                        }
+                    }
+                }
+                (Readiness::Healthcheck, _) => {
+                    let container = docker
+                        .inspect_container(&container_ids[i], None::<InspectContainerOptions>)
+                        .await
+                        .unwrap();
+                    let healthy = container
+                        .state
+                        .and_then(|state| state.health)
+                        .and_then(|health| health.status)
+                        == Some(HealthStatusEnum::HEALTHY);
+                    if healthy {
+                        ready[i] = true;
+                        trace!("service {} ready via healthcheck", service.name);
+                    }
+                }
+                (Readiness::LogRegex(_), None) => unreachable!(
+                    "a log-regex service always has a log stream"
+                ),
+            }
+        }
+
+        if ready.iter().all(|x| *x) {
+            trace!("All services ready in {}", instant.elapsed().as_secs());
+            return;
+        }
+
+        // Check for a container that has already failed so we can report it a lot sooner than
+        // just relying on the timeout, matching the subprocess backend's behaviour.
+        crate::DockerCompose::assert_no_containers_in_service_with_status(compose_files, "exited");
+        crate::DockerCompose::assert_no_containers_in_service_with_status(compose_files, "dead");
+        crate::DockerCompose::assert_no_containers_in_service_with_status(
+            compose_files,
+            "removing",
+        );
+
+        if instant.elapsed() > timeout {
+            let missing: Vec<&str> = services
+                .iter()
+                .zip(ready.iter())
+                .filter(|(_, ready)| !**ready)
+                .map(|(service, _)| service.name.as_str())
+                .collect();
+            panic!("wait_for_log {timeout:?} timer expired. Services still not ready: {missing:?}");
+        }
+    }
+}
@@ -1,12 +1,17 @@
+#[cfg(feature = "bollard")]
+mod bollard_backend;
+
 use anyhow::{Result, anyhow};
 use regex::Regex;
 use serde_yaml::Value;
 use std::collections::HashMap;
 use std::fmt::Write;
-use std::io::ErrorKind;
+use std::io::{BufRead, BufReader, ErrorKind};
 use std::process::Command;
+use std::sync::mpsc;
+use std::thread;
 use std::time::{self, Duration};
-use subprocess::{Exec, Redirection};
+use subprocess::{Exec, Popen, Redirection};
 use tracing::trace;
 
 /// Runs a command and returns the output as a string.
@@ -37,11 +42,31 @@ pub(crate) fn run_command(command: &str, args: &[&str]) -> Result<String> {
     }
 }
 
+/// Builds the interleaved `-f <path>` arguments for a `docker compose` invocation across every
+/// compose file layered onto an instance: the primary file plus any overrides, e.g. the env
+/// override file generated by `new_with_env`.
+pub(crate) fn compose_file_args(compose_files: &[String]) -> Vec<&str> {
+    compose_files
+        .iter()
+        .flat_map(|path| ["-f", path.as_str()])
+        .collect()
+}
+
 /// Launch and manage a docker compose instance
 #[must_use]
 pub struct DockerCompose {
-    file_path: String,
+    // The primary compose file followed by any override files (e.g. from `new_with_env`), passed to
+    // `docker compose` as repeated `-f` arguments.
+    compose_files: Vec<String>,
     services: Vec<Service>,
+    // Only set when the compose definition was written out by `from_definition`. Never read:
+    // held purely so its `Drop` removes the temp file alongside the usual `clean_up`.
+    #[allow(dead_code)]
+    temp_file: Option<tempfile::TempPath>,
+    // Only set when environment overrides were requested via `new_with_env`. Never read: held
+    // purely so its `Drop` removes the generated override file alongside the usual `clean_up`.
+    #[allow(dead_code)]
+    env_override_file: Option<tempfile::TempPath>,
 }
 
 impl DockerCompose {
@@ -50,6 +75,7 @@ impl DockerCompose {
     ///
     /// image_waiters gives DockerCompose a way to know when a container has finished starting up.
     /// Each entry defines an image name and a regex such that if the regex matches on a log line output by a container running that image the container is considered started up.
+    /// An entry's `name` matches regardless of the image's tag; set `version` to scope an entry to a specific tag.
     ///
     /// image_builder is a callback allowing the user to build a docker image if the docker-compose.yaml depends on it.
     /// The argument is an iterator over all the image names docker compose is going to use.
@@ -57,6 +83,102 @@ impl DockerCompose {
         image_waiters: &'static [Image],
         image_builder: impl FnOnce(&[&str]),
         yaml_path: &str,
+    ) -> Self {
+        DockerCompose::launch(
+            image_waiters,
+            image_builder,
+            yaml_path.to_string(),
+            None,
+            vec![],
+            None,
+        )
+    }
+
+    /// Runs docker compose on the provided compose definition, without requiring it to live in a file on disk.
+    /// The definition is written out to a managed temporary file that is cleaned up when the returned object is dropped.
+    /// Dropping the returned object will stop and destroy the launched docker compose services.
+    ///
+    /// This is useful for tests that want to template a compose file (e.g. to vary ports or image versions per test)
+    /// without maintaining a near-duplicate `.yaml` file for each variation.
+    ///
+    /// image_waiters and image_builder behave the same as in [`DockerCompose::new`].
+    pub fn from_definition(
+        image_waiters: &'static [Image],
+        image_builder: impl FnOnce(&[&str]),
+        yaml: &str,
+    ) -> Self {
+        let mut temp_file = tempfile::Builder::new()
+            .suffix(".yaml")
+            .tempfile()
+            .unwrap();
+        std::io::Write::write_all(&mut temp_file, yaml.as_bytes()).unwrap();
+        let temp_path = temp_file.into_temp_path();
+        let file_path = temp_path.to_str().unwrap().to_string();
+
+        DockerCompose::launch(
+            image_waiters,
+            image_builder,
+            file_path,
+            Some(temp_path),
+            vec![],
+            None,
+        )
+    }
+
+    /// Runs docker compose like [`DockerCompose::new`], additionally overriding or injecting
+    /// environment variables for specific services at startup.
+    ///
+    /// `env` lists, per service, the `(key, value)` pairs to set; it is rendered into a generated
+    /// compose override file merged on top of `yaml_path` via an extra `-f`, so a single
+    /// committed compose file can be reused across tests that need different credentials,
+    /// feature flags, or cluster sizes without editing the YAML.
+    pub fn new_with_env(
+        image_waiters: &'static [Image],
+        image_builder: impl FnOnce(&[&str]),
+        yaml_path: &str,
+        env: &[(&str, &[(&str, &str)])],
+    ) -> Self {
+        let mut override_file = tempfile::Builder::new()
+            .suffix(".yaml")
+            .tempfile()
+            .unwrap();
+        let override_yaml = DockerCompose::render_env_override(env);
+        std::io::Write::write_all(&mut override_file, override_yaml.as_bytes()).unwrap();
+        let override_path = override_file.into_temp_path();
+        let override_path_string = override_path.to_str().unwrap().to_string();
+
+        DockerCompose::launch(
+            image_waiters,
+            image_builder,
+            yaml_path.to_string(),
+            None,
+            vec![override_path_string],
+            Some(override_path),
+        )
+    }
+
+    /// Renders a compose override document setting `environment` for each service in `env`.
+    fn render_env_override(env: &[(&str, &[(&str, &str)])]) -> String {
+        let mut yaml = "services:\n".to_owned();
+        for (service_name, vars) in env {
+            writeln!(yaml, "  {service_name}:").unwrap();
+            writeln!(yaml, "    environment:").unwrap();
+            for (key, value) in *vars {
+                // `{value:?}` renders an escaped, double-quoted string, which is also a valid
+                // YAML scalar.
+                writeln!(yaml, "      {key}: {value:?}").unwrap();
+            }
+        }
+        yaml
+    }
+
+    fn launch(
+        image_waiters: &'static [Image],
+        image_builder: impl FnOnce(&[&str]),
+        yaml_path: String,
+        temp_file: Option<tempfile::TempPath>,
+        extra_compose_files: Vec<String>,
+        env_override_file: Option<tempfile::TempPath>,
     ) -> Self {
         match Command::new("docker")
             .arg("compose")
@@ -72,52 +194,60 @@ impl DockerCompose {
             }
         }
 
+        let compose_files: Vec<String> = std::iter::once(yaml_path.clone())
+            .chain(extra_compose_files)
+            .collect();
+
         // It is critical that clean_up is run before everything else as the internal `docker compose` commands act as validation
         // for the docker-compose.yaml file that we later manually parse with poor error handling
-        DockerCompose::clean_up(yaml_path).unwrap();
+        DockerCompose::clean_up(&compose_files).unwrap();
 
-        let service_to_image = DockerCompose::get_service_to_image(yaml_path);
+        // Overrides only set environment variables, they never add services, so the image list
+        // and healthcheck declarations are always read from the primary compose file.
+        let compose_file = DockerCompose::parse_compose_file(&yaml_path);
 
-        let images: Vec<&str> = service_to_image.values().map(|x| x.as_ref()).collect();
+        let images: Vec<&str> = compose_file
+            .services
+            .values()
+            .map(|service| service.image.as_ref())
+            .collect();
         image_builder(&images);
 
-        run_command("docker", &["compose", "-f", yaml_path, "up", "-d"]).unwrap();
+        let mut up_args = compose_file_args(&compose_files);
+        up_args.extend(["up", "-d"]);
+        run_command("docker", &up_args).unwrap();
 
-        let mut services = DockerCompose::get_services(image_waiters, service_to_image);
+        let mut services = DockerCompose::get_services(image_waiters, compose_file.services);
         let mut services_arg: Vec<&mut Service> = services.iter_mut().collect();
-        DockerCompose::wait_for_logs(yaml_path, &mut services_arg);
+        DockerCompose::wait_for_logs(&compose_files, &mut services_arg);
 
         DockerCompose {
-            file_path: yaml_path.to_string(),
+            compose_files,
             services,
+            temp_file,
+            env_override_file,
         }
     }
 
     /// Stops the container with the provided service name
     pub fn stop_service(&self, service_name: &str) {
-        run_command(
-            "docker",
-            &["compose", "-f", &self.file_path, "stop", service_name],
-        )
-        .unwrap();
+        let mut args = compose_file_args(&self.compose_files);
+        args.extend(["stop", service_name]);
+        run_command("docker", &args).unwrap();
     }
 
     /// Kills the container with the provided service name
     pub fn kill_service(&self, service_name: &str) {
-        run_command(
-            "docker",
-            &["compose", "-f", &self.file_path, "kill", service_name],
-        )
-        .unwrap();
+        let mut args = compose_file_args(&self.compose_files);
+        args.extend(["kill", service_name]);
+        run_command("docker", &args).unwrap();
     }
 
     /// Restarts the container with the provided service name
     pub fn start_service(&mut self, service_name: &str) {
-        run_command(
-            "docker",
-            &["compose", "-f", &self.file_path, "start", service_name],
-        )
-        .unwrap();
+        let mut args = compose_file_args(&self.compose_files);
+        args.extend(["start", service_name]);
+        run_command("docker", &args).unwrap();
 
         // service must exist because previous command succeeded
         let service = self
@@ -125,59 +255,129 @@ impl DockerCompose {
             .iter_mut()
             .find(|x| x.name == service_name)
             .unwrap();
-        DockerCompose::wait_for_logs(&self.file_path, &mut [service]);
+        DockerCompose::wait_for_logs(&self.compose_files, &mut [service]);
+    }
+
+    /// Returns the host port that `container_port` on `service_name` is published to.
+    ///
+    /// Resolves the mapping via `docker compose port`, so this works whether the compose file
+    /// pins the host port (e.g. `"8080:80"`) or leaves it to docker to assign one dynamically
+    /// (e.g. `"80"`), which is what lets multiple `DockerCompose` instances of the same compose
+    /// file run concurrently without port collisions.
+    pub fn host_port(&self, service_name: &str, container_port: u16) -> u16 {
+        let container_port = container_port.to_string();
+        let mut args = compose_file_args(&self.compose_files);
+        args.extend(["port", service_name, &container_port]);
+        let output = run_command("docker", &args).unwrap();
+
+        output
+            .trim()
+            .rsplit(':')
+            .next()
+            .unwrap()
+            .parse()
+            .unwrap_or_else(|err| {
+                panic!("failed to parse host port from `docker compose port` output {output:?}: {err}")
+            })
     }
 
-    /// constructs one service per service_to_image, the waiting regex is taken from the corresponding image entry in image_waiters.
+    /// Returns the `host:port` address that `container_port` on `service_name` is published to.
+    pub fn service_address(&self, service_name: &str, container_port: u16) -> String {
+        format!("127.0.0.1:{}", self.host_port(service_name, container_port))
+    }
+
+    /// constructs one service per entry in the compose file's `services` map.
+    /// Services that declare an enabled `healthcheck` wait on the `docker compose ps` health
+    /// status; all others (including services whose healthcheck is disabled) wait on the regex
+    /// taken from the corresponding image entry in image_waiters.
     fn get_services(
         image_waiters: &[Image],
-        service_to_image: HashMap<String, String>,
+        services: HashMap<String, ComposeService>,
     ) -> Vec<Service> {
-        service_to_image
+        services
             .into_iter()
-            .map(
-                |(service_name, image_name)| match image_waiters.iter().find(|image| image.name == image_name) {
-                    Some(image) => Service::new(service_name, image),
-                    None => panic!("The image_waiters list given to DockerCompose::new does not include the image {image_name}, please add it to the list."),
-                },
-            )
+            .map(|(service_name, service)| {
+                if service.healthcheck.as_ref().is_some_and(|h| !h.is_disabled()) {
+                    Service::new_healthcheck(service_name)
+                } else {
+                    match DockerCompose::find_image_waiter(image_waiters, &service.image) {
+                        Some(image) => Service::new_log_regex(service_name, image),
+                        None => panic!("The image_waiters list given to DockerCompose::new does not include the image {}, please add it to the list.", service.image),
+                    }
+                }
+            })
             .collect()
     }
 
-    fn get_service_to_image(file_path: &str) -> HashMap<String, String> {
-        let compose_yaml: Value =
-            serde_yaml::from_str(&std::fs::read_to_string(file_path).unwrap()).unwrap();
-        let mut result = HashMap::new();
-        match compose_yaml {
-            Value::Mapping(root) => match root.get("services").unwrap() {
-                Value::Mapping(services) => {
-                    for (service_name, service) in services {
-                        let service_name = match service_name {
-                            Value::String(service_name) => service_name,
-                            service_name => panic!("Unexpected service_name {service_name:?}"),
-                        };
-                        match service {
-                            Value::Mapping(service) => {
-                                let image = match service.get("image").unwrap() {
-                                    Value::String(image) => image,
-                                    image => panic!("Unexpected image {image:?}"),
-                                };
-                                result.insert(service_name.clone(), image.clone());
-                            }
-                            service => panic!("Unexpected service {service:?}"),
-                        }
+    /// Finds the waiter that matches `image_name`, preferring a waiter whose `version` matches
+    /// the image's tag over one that only matches the repository name.
+    fn find_image_waiter<'a>(image_waiters: &'a [Image], image_name: &str) -> Option<&'a Image> {
+        let (repository, tag) = DockerCompose::split_repository_and_tag(image_name);
+
+        let matches: Vec<&Image> = image_waiters
+            .iter()
+            .filter(|image| {
+                image.name == repository
+                    && match image.version {
+                        Some(version) => Some(version) == tag,
+                        None => true,
                     }
-                }
-                services => panic!("Unexpected services {services:?}"),
-            },
-            root => panic!("Unexpected root {root:?}"),
+            })
+            .collect();
+
+        // Prefer the waiter scoped to this exact version over a version-agnostic one.
+        matches
+            .iter()
+            .find(|image| image.version.is_some())
+            .or_else(|| matches.first())
+            .copied()
+    }
+
+    /// Splits a Docker image reference into its repository and tag, following the standard
+    /// Docker convention of splitting on the *last* `:` that occurs after the *last* `/` —
+    /// not the first `:` overall, which would misparse a `registry.example.com:5000/...`
+    /// host:port prefix as the tag separator.
+    fn split_repository_and_tag(image_name: &str) -> (&str, Option<&str>) {
+        let after_slash = image_name.rfind('/').map_or(0, |i| i + 1);
+        match image_name[after_slash..].rfind(':') {
+            Some(i) => {
+                let colon = after_slash + i;
+                (&image_name[..colon], Some(&image_name[colon + 1..]))
+            }
+            None => (image_name, None),
         }
-        result
+    }
+
+    /// Parses the compose file into a typed [`ComposeFile`], giving clearer errors than hand
+    /// walking a `serde_yaml::Value` tree when the file doesn't look as expected.
+    fn parse_compose_file(file_path: &str) -> ComposeFile {
+        let yaml = std::fs::read_to_string(file_path).unwrap();
+        serde_yaml::from_str(&yaml)
+            .unwrap_or_else(|err| panic!("Failed to parse compose file {file_path}: {err}"))
     }
 
     /// Wait until the requirements in every Service is met.
     /// Will panic if a timeout occurs.
-    fn wait_for_logs(file_path: &str, services: &mut [&mut Service]) {
+    ///
+    /// When the `bollard` feature is enabled this streams container logs directly from the
+    /// Docker API instead of repeatedly shelling out to `docker compose logs`; otherwise it
+    /// falls back to the subprocess implementation below.
+    fn wait_for_logs(compose_files: &[String], services: &mut [&mut Service]) {
+        #[cfg(feature = "bollard")]
+        {
+            crate::bollard_backend::wait_for_logs(compose_files, services);
+        }
+        #[cfg(not(feature = "bollard"))]
+        {
+            DockerCompose::wait_for_logs_subprocess(compose_files, services);
+        }
+    }
+
+    /// Spawns one `docker compose logs --follow` child per log-regex service and scans only newly
+    /// arrived lines through each service's readiness regex, instead of recapturing and rescanning
+    /// the whole accumulated log on every poll iteration. Healthcheck services are polled instead.
+    #[cfg(not(feature = "bollard"))]
+    fn wait_for_logs_subprocess(compose_files: &[String], services: &mut [&mut Service]) {
         // Find the service with the maximum timeout and use that
         let timeout = services
             .iter()
@@ -186,133 +386,320 @@ impl DockerCompose {
             .unwrap();
 
         // TODO: remove this check once CI docker compose is updated (probably ubuntu 22.04)
-        let can_use_status_flag =
-            run_command("docker", &["compose", "-f", file_path, "ps", "--help"])
+        let can_use_status_flag = {
+            let mut args = compose_file_args(compose_files);
+            args.extend(["ps", "--help"]);
+            run_command("docker", &args).unwrap().contains("--status")
+        };
+
+        // `docker compose logs --follow` replays the container's entire log history before
+        // tailing new output, and that history survives a `stop`/`start` restart. Passing
+        // `--since` scoped to the start of this particular wait ensures a line logged by a
+        // previous run (e.g. before `start_service` restarted the container) can't be
+        // mistaken for a fresh readiness match.
+        let since = format!(
+            "{}",
+            time::SystemTime::now()
+                .duration_since(time::UNIX_EPOCH)
                 .unwrap()
-                .contains("--status");
+                .as_secs()
+        );
+
+        let (tx, rx) = mpsc::channel();
+        let mut followers: Vec<Popen> = services
+            .iter()
+            .enumerate()
+            .filter_map(|(i, service)| {
+                // Services with a declared healthcheck are polled via `docker compose ps`
+                // instead, so there is nothing to follow here.
+                if !matches!(service.readiness, Readiness::LogRegex(_)) {
+                    return None;
+                }
+
+                let mut args = compose_file_args(compose_files);
+                args.extend([
+                    "logs",
+                    "--follow",
+                    "--no-log-prefix",
+                    "--since",
+                    &since,
+                    &service.name,
+                ]);
+                let mut follower = Exec::cmd("docker")
+                    .args(&args)
+                    .stdout(Redirection::Pipe)
+                    .stderr(Redirection::Merge)
+                    .popen()
+                    .unwrap();
+                let stdout = follower.stdout.take().unwrap();
+                let tx = tx.clone();
+                thread::spawn(move || {
+                    for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                        if tx.send((i, line)).is_err() {
+                            return;
+                        }
+                    }
+                });
+                Some(follower)
+            })
+            .collect();
+
+        // One entry per service, in the order services was given: `Some` once ready, carrying
+        // the matched log line for log-regex services or `None` for healthcheck services.
+        let mut ready: Vec<Option<Option<String>>> = vec![None; services.len()];
 
         let instant = time::Instant::now();
-        loop {
-            // check if every service is completely ready
-            if services.iter().all(|service| {
-                let log = run_command(
-                    "docker",
-                    &["compose", "-f", file_path, "logs", &service.name],
-                )
-                .unwrap();
-                service.log_to_wait_for.find_iter(&log).count() > service.logs_seen
-            }) {
-                for service in services.iter_mut() {
-                    service.logs_seen += 1;
+        let result = loop {
+            for (i, line) in rx.try_iter() {
+                if let Readiness::LogRegex(regex) = &services[i].readiness {
+                    if ready[i].is_none() && regex.is_match(&line) {
+                        ready[i] = Some(Some(line));
+                    }
+                }
+            }
+
+            for (i, service) in services.iter().enumerate() {
+                if ready[i].is_none()
+                    && matches!(service.readiness, Readiness::Healthcheck)
+                    && DockerCompose::is_healthy(compose_files, &service.name)
+                {
+                    ready[i] = Some(None);
                 }
-                let time_to_complete = instant.elapsed();
-                trace!("All services ready in {}", time_to_complete.as_secs());
-                return;
             }
 
-            let all_logs = run_command("docker", &["compose", "-f", file_path, "logs"]).unwrap();
+            if ready.iter().all(Option::is_some) {
+                trace!("All services ready in {}", instant.elapsed().as_secs());
+                break Ok(());
+            }
 
             // check if the service has failed in some way
             // this allows us to report the failure to the developer a lot sooner than just relying on the timeout
             if can_use_status_flag {
+                DockerCompose::assert_no_containers_in_service_with_status(compose_files, "exited");
+                DockerCompose::assert_no_containers_in_service_with_status(compose_files, "dead");
                 DockerCompose::assert_no_containers_in_service_with_status(
-                    file_path, "exited", &all_logs,
-                );
-                DockerCompose::assert_no_containers_in_service_with_status(
-                    file_path, "dead", &all_logs,
-                );
-                DockerCompose::assert_no_containers_in_service_with_status(
-                    file_path, "removing", &all_logs,
+                    compose_files,
+                    "removing",
                 );
             }
 
             // if all else fails timeout the wait
             if instant.elapsed() > timeout {
                 let mut results = "".to_owned();
-                for service in services {
-                    let log = run_command(
-                        "docker",
-                        &["compose", "-f", file_path, "logs", &service.name],
-                    )
-                    .unwrap();
-                    let found = if service.log_to_wait_for.is_match(&log) {
-                        "Found"
-                    } else {
-                        "Missing"
-                    };
-
-                    writeln!(
-                        results,
-                        "*    Service {}, searched for '{}', was {}",
-                        service.name, service.log_to_wait_for, found
-                    )
+                for (service, found) in services.iter().zip(&ready) {
+                    match (&service.readiness, found) {
+                        (Readiness::LogRegex(regex), Some(Some(line))) => writeln!(
+                            results,
+                            "*    Service {}, searched for '{regex}', was Found in line: {line}",
+                            service.name,
+                        ),
+                        (Readiness::LogRegex(regex), None) => writeln!(
+                            results,
+                            "*    Service {}, searched for '{regex}', was Missing",
+                            service.name,
+                        ),
+                        (Readiness::Healthcheck, Some(_)) => writeln!(
+                            results,
+                            "*    Service {}, waited for healthcheck, was Healthy",
+                            service.name,
+                        ),
+                        (Readiness::Healthcheck, None) => writeln!(
+                            results,
+                            "*    Service {}, waited for healthcheck, was Unhealthy",
+                            service.name,
+                        ),
+                        (Readiness::LogRegex(_), Some(None)) => unreachable!(
+                            "log-regex services are only ever marked ready with a matched line"
+                        ),
+                    }
                     .unwrap();
                 }
 
-                panic!(
+                let all_logs = {
+                    let mut args = compose_file_args(compose_files);
+                    args.push("logs");
+                    run_command("docker", &args).unwrap()
+                };
+                break Err(format!(
                     "wait_for_log {timeout:?} timer expired. Results:\n{results}\nLogs:\n{all_logs}"
-                );
+                ));
             }
+
+            thread::sleep(Duration::from_millis(100));
+        };
+
+        for follower in &mut followers {
+            let _ = follower.terminate();
+            let _ = follower.wait();
+        }
+
+        if let Err(message) = result {
+            panic!("{message}");
         }
     }
 
-    fn assert_no_containers_in_service_with_status(file_path: &str, status: &str, full_log: &str) {
-        let containers = run_command(
-            "docker",
-            &["compose", "-f", file_path, "ps", "--status", status],
-        )
-        .unwrap();
+    fn assert_no_containers_in_service_with_status(compose_files: &[String], status: &str) {
+        let containers = {
+            let mut args = compose_file_args(compose_files);
+            args.extend(["ps", "--status", status]);
+            run_command("docker", &args).unwrap()
+        };
         // One line for the table heading. If there are more lines then there is some data indicating that containers exist with this status
         if containers.matches('\n').count() > 1 {
-            panic!(
-                "At least one container failed to initialize\n{containers}\nFull log\n{full_log}"
-            );
+            let all_logs = {
+                let mut args = compose_file_args(compose_files);
+                args.push("logs");
+                run_command("docker", &args).unwrap()
+            };
+            panic!("At least one container failed to initialize\n{containers}\nFull log\n{all_logs}");
         }
     }
 
+    /// Returns whether `docker inspect` reports the health status of the named service's
+    /// container (found via `docker compose ps -q`) as `healthy`.
+    #[cfg(not(feature = "bollard"))]
+    fn is_healthy(compose_files: &[String], service_name: &str) -> bool {
+        let container_id = {
+            let mut args = compose_file_args(compose_files);
+            args.extend(["ps", "-q", service_name]);
+            run_command("docker", &args).unwrap()
+        };
+        let container_id = container_id.trim();
+        if container_id.is_empty() {
+            return false;
+        }
+
+        let status = run_command(
+            "docker",
+            &[
+                "inspect",
+                "--format",
+                "{{.State.Health.Status}}",
+                container_id,
+            ],
+        )
+        .unwrap();
+        status.trim() == "healthy"
+    }
+
     /// Cleans up docker compose by shutting down the running system and removing the images.
     ///
     /// # Arguments
-    /// * `file_path` - The path to the docker-compose yaml file that was used to start docker.
-    fn clean_up(file_path: &str) -> Result<()> {
-        trace!("bringing down docker compose {}", file_path);
+    /// * `compose_files` - The compose files that were used to start docker, in `-f` order.
+    fn clean_up(compose_files: &[String]) -> Result<()> {
+        trace!("bringing down docker compose {:?}", compose_files);
+
+        let mut kill_args = compose_file_args(compose_files);
+        kill_args.push("kill");
+        run_command("docker", &kill_args)?;
 
-        run_command("docker", &["compose", "-f", file_path, "kill"])?;
-        run_command("docker", &["compose", "-f", file_path, "down", "-v"])?;
+        let mut down_args = compose_file_args(compose_files);
+        down_args.extend(["down", "-v"]);
+        run_command("docker", &down_args)?;
 
         Ok(())
     }
 }
 
 pub struct Image {
+    /// The repository portion of the image name, e.g. `bitnami/redis` without a `:tag` suffix.
+    /// Matched against the image regardless of which tag the compose file uses.
     pub name: &'static str,
+    /// Restricts this waiter to a specific tag, e.g. `6.2.13-debian-11-r73`.
+    /// Only needed when the ready-log differs between versions of the same image;
+    /// leave as `None` to match every tag of `name`.
+    pub version: Option<&'static str>,
+    /// Ignored for services that declare an enabled `healthcheck` in the compose file; those wait on the
+    /// `docker compose ps` health status instead.
     pub log_regex_to_wait_for: &'static str,
     pub timeout: Duration,
 }
 
+/// A parsed `docker-compose.yaml`, typed just enough to drive readiness checks, rather than hand
+/// walking a `serde_yaml::Value` tree.
+#[derive(serde::Deserialize)]
+struct ComposeFile {
+    services: HashMap<String, ComposeService>,
+}
+
+#[derive(serde::Deserialize)]
+struct ComposeService {
+    image: String,
+    #[serde(default)]
+    healthcheck: Option<Healthcheck>,
+    // No `ports` field: `DockerCompose::host_port` resolves published ports by querying
+    // `docker compose port` directly rather than by parsing the compose file's `ports:` mapping.
+}
+
+/// Only the fields needed to tell that a service declares a healthcheck at all, and whether that
+/// healthcheck is actually enabled; the exact test command otherwise is left to `docker
+/// compose`/the daemon to evaluate.
+#[derive(serde::Deserialize)]
+struct Healthcheck {
+    #[serde(default)]
+    disable: bool,
+    #[serde(default)]
+    test: Option<Value>,
+}
+
+impl Healthcheck {
+    /// True if this healthcheck is explicitly turned off via `disable: true` or the legacy
+    /// `test: ["NONE"]`/`test: "NONE"` form. Docker never reports a `Health` status for such a
+    /// service, so [`DockerCompose::get_services`] must not wait on one.
+    fn is_disabled(&self) -> bool {
+        if self.disable {
+            return true;
+        }
+        match &self.test {
+            Some(Value::Sequence(test)) => test.first().and_then(Value::as_str) == Some("NONE"),
+            Some(Value::String(test)) => test == "NONE",
+            _ => false,
+        }
+    }
+}
+
+/// Timeout for a [`Service`] waiting on its `healthcheck` rather than an [`Image`]'s log regex,
+/// since there's no per-image `Image::timeout` to draw on for those.
+const HEALTHCHECK_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// How a [`Service`] determines that it has finished starting up.
+pub(crate) enum Readiness {
+    /// Wait for a line of the container's logs to match this regex.
+    LogRegex(Regex),
+    /// Wait for `docker compose ps` to report the container as `healthy`, because the compose
+    /// file declares a `healthcheck` for this service.
+    Healthcheck,
+}
+
 /// Holds the state for a running service
-struct Service {
-    name: String,
-    log_to_wait_for: Regex,
-    logs_seen: usize,
-    timeout: Duration,
+pub(crate) struct Service {
+    pub(crate) name: String,
+    pub(crate) readiness: Readiness,
+    pub(crate) timeout: Duration,
 }
 
 impl Service {
-    fn new(name: String, image: &Image) -> Service {
+    fn new_log_regex(name: String, image: &Image) -> Service {
         Service {
             name,
-            log_to_wait_for: Regex::new(image.log_regex_to_wait_for).unwrap(),
-            logs_seen: 0,
+            readiness: Readiness::LogRegex(Regex::new(image.log_regex_to_wait_for).unwrap()),
             timeout: image.timeout,
         }
     }
+
+    fn new_healthcheck(name: String) -> Service {
+        Service {
+            name,
+            readiness: Readiness::Healthcheck,
+            timeout: HEALTHCHECK_TIMEOUT,
+        }
+    }
 }
 
 impl Drop for DockerCompose {
     fn drop(&mut self) {
         if std::thread::panicking() {
-            if let Err(err) = DockerCompose::clean_up(&self.file_path) {
+            if let Err(err) = DockerCompose::clean_up(&self.compose_files) {
                 // We need to use println! here instead of error! because error! does not
                 // get output when panicking
                 println!(
@@ -320,7 +707,185 @@ impl Drop for DockerCompose {
                 );
             }
         } else {
-            DockerCompose::clean_up(&self.file_path).unwrap();
+            DockerCompose::clean_up(&self.compose_files).unwrap();
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_repository_and_tag_with_no_tag() {
+        assert_eq!(
+            DockerCompose::split_repository_and_tag("bitnami/redis"),
+            ("bitnami/redis", None)
+        );
+    }
+
+    #[test]
+    fn split_repository_and_tag_with_tag() {
+        assert_eq!(
+            DockerCompose::split_repository_and_tag("bitnami/redis:6.2"),
+            ("bitnami/redis", Some("6.2"))
+        );
+    }
+
+    #[test]
+    fn split_repository_and_tag_with_registry_host_port() {
+        // The port's colon must not be mistaken for the tag separator.
+        assert_eq!(
+            DockerCompose::split_repository_and_tag("registry.example.com:5000/bitnami/redis:6.2"),
+            ("registry.example.com:5000/bitnami/redis", Some("6.2"))
+        );
+    }
+
+    #[test]
+    fn split_repository_and_tag_with_registry_host_port_and_no_tag() {
+        assert_eq!(
+            DockerCompose::split_repository_and_tag("registry.example.com:5000/bitnami/redis"),
+            ("registry.example.com:5000/bitnami/redis", None)
+        );
+    }
+
+    const REDIS_IMAGE: Image = Image {
+        name: "bitnami/redis",
+        version: None,
+        log_regex_to_wait_for: "Ready to accept connections",
+        timeout: Duration::from_secs(120),
+    };
+    const REDIS_6_IMAGE: Image = Image {
+        name: "bitnami/redis",
+        version: Some("6.2"),
+        log_regex_to_wait_for: "Ready to accept connections 6.2",
+        timeout: Duration::from_secs(120),
+    };
+
+    #[test]
+    fn find_image_waiter_matches_by_repository() {
+        let waiters = [REDIS_IMAGE];
+        let found = DockerCompose::find_image_waiter(&waiters, "bitnami/redis:6.2").unwrap();
+        assert_eq!(found.name, "bitnami/redis");
+    }
+
+    #[test]
+    fn find_image_waiter_prefers_version_scoped_waiter() {
+        let waiters = [REDIS_IMAGE, REDIS_6_IMAGE];
+        let found = DockerCompose::find_image_waiter(&waiters, "bitnami/redis:6.2").unwrap();
+        assert_eq!(found.version, Some("6.2"));
+    }
+
+    #[test]
+    fn find_image_waiter_handles_registry_host_port() {
+        let waiters = [Image {
+            name: "registry.example.com:5000/bitnami/redis",
+            ..REDIS_6_IMAGE
+        }];
+        let found =
+            DockerCompose::find_image_waiter(&waiters, "registry.example.com:5000/bitnami/redis:6.2")
+                .unwrap();
+        assert_eq!(found.version, Some("6.2"));
+    }
+
+    #[test]
+    fn find_image_waiter_returns_none_when_unmatched() {
+        let waiters = [REDIS_6_IMAGE];
+        assert!(DockerCompose::find_image_waiter(&waiters, "bitnami/postgres").is_none());
+    }
+
+    #[test]
+    fn healthcheck_is_enabled_by_default() {
+        let healthcheck = Healthcheck {
+            disable: false,
+            test: None,
+        };
+        assert!(!healthcheck.is_disabled());
+    }
+
+    #[test]
+    fn healthcheck_disabled_via_disable_flag() {
+        let healthcheck = Healthcheck {
+            disable: true,
+            test: None,
+        };
+        assert!(healthcheck.is_disabled());
+    }
+
+    #[test]
+    fn healthcheck_disabled_via_legacy_test_none_sequence() {
+        let healthcheck = Healthcheck {
+            disable: false,
+            test: Some(Value::Sequence(vec![Value::String("NONE".to_owned())])),
+        };
+        assert!(healthcheck.is_disabled());
+    }
+
+    #[test]
+    fn healthcheck_disabled_via_legacy_test_none_string() {
+        let healthcheck = Healthcheck {
+            disable: false,
+            test: Some(Value::String("NONE".to_owned())),
+        };
+        assert!(healthcheck.is_disabled());
+    }
+
+    #[test]
+    fn get_services_waits_on_healthcheck_when_enabled() {
+        let mut services = HashMap::new();
+        services.insert(
+            "redis".to_owned(),
+            ComposeService {
+                image: "bitnami/redis".to_owned(),
+                healthcheck: Some(Healthcheck {
+                    disable: false,
+                    test: None,
+                }),
+            },
+        );
+
+        let result = DockerCompose::get_services(&[REDIS_IMAGE], services);
+        assert_eq!(result.len(), 1);
+        assert!(matches!(result[0].readiness, Readiness::Healthcheck));
+    }
+
+    #[test]
+    fn get_services_falls_back_to_log_regex_when_healthcheck_disabled() {
+        let mut services = HashMap::new();
+        services.insert(
+            "redis".to_owned(),
+            ComposeService {
+                image: "bitnami/redis".to_owned(),
+                healthcheck: Some(Healthcheck {
+                    disable: true,
+                    test: None,
+                }),
+            },
+        );
+
+        let result = DockerCompose::get_services(&[REDIS_IMAGE], services);
+        assert_eq!(result.len(), 1);
+        assert!(matches!(result[0].readiness, Readiness::LogRegex(_)));
+    }
+
+    #[test]
+    fn render_env_override_renders_environment_per_service() {
+        let yaml = DockerCompose::render_env_override(&[("redis", &[("FOO", "bar")])]);
+        assert_eq!(
+            yaml,
+            "services:\n  redis:\n    environment:\n      FOO: \"bar\"\n"
+        );
+    }
+
+    #[test]
+    fn render_env_override_renders_multiple_services_and_vars() {
+        let yaml = DockerCompose::render_env_override(&[
+            ("redis", &[("FOO", "bar"), ("BAZ", "qux")]),
+            ("postgres", &[("PASSWORD", "secret")]),
+        ]);
+        assert_eq!(
+            yaml,
+            "services:\n  redis:\n    environment:\n      FOO: \"bar\"\n      BAZ: \"qux\"\n  postgres:\n    environment:\n      PASSWORD: \"secret\"\n"
+        );
+    }
+}
@@ -6,8 +6,9 @@ use std::time::Duration;
 fn test() {
     // loop multiple times to test cleanup
     for _ in 0..3 {
-        let _redis = DockerCompose::new(&IMAGE_WAITERS, |_| {}, "tests/docker-compose.yaml");
-        let client = redis::Client::open("redis://127.0.0.1/").unwrap();
+        let redis = DockerCompose::new(&IMAGE_WAITERS, |_| {}, "tests/docker-compose.yaml");
+        let address = redis.service_address("redis", 6379);
+        let client = redis::Client::open(format!("redis://{address}")).unwrap();
         let mut con = client.get_connection().unwrap();
         let _: () = con.set("my_key", 42).unwrap();
         let result: i32 = con.get("my_key").unwrap();
@@ -15,8 +16,50 @@ fn test() {
     }
 }
 
+#[test]
+fn test_from_definition() {
+    let redis = DockerCompose::from_definition(
+        &IMAGE_WAITERS,
+        |_| {},
+        r#"
+services:
+  redis:
+    image: bitnami/redis
+    environment:
+      ALLOW_EMPTY_PASSWORD: "yes"
+    ports:
+      - "6379"
+"#,
+    );
+    let address = redis.service_address("redis", 6379);
+    let client = redis::Client::open(format!("redis://{address}")).unwrap();
+    let mut con = client.get_connection().unwrap();
+    let _: () = con.set("my_key", 42).unwrap();
+    let result: i32 = con.get("my_key").unwrap();
+    assert_eq!(result, 42);
+}
+
+#[test]
+fn test_new_with_env() {
+    // Reuses the checked-in compose file, injecting a password via an env override file instead
+    // of maintaining a near-duplicate compose file just to vary this one setting.
+    let redis = DockerCompose::new_with_env(
+        &IMAGE_WAITERS,
+        |_| {},
+        "tests/docker-compose.yaml",
+        &[("redis", &[("REDIS_PASSWORD", "my_password")])],
+    );
+    let address = redis.service_address("redis", 6379);
+    let client = redis::Client::open(format!("redis://:my_password@{address}")).unwrap();
+    let mut con = client.get_connection().unwrap();
+    let _: () = con.set("my_key", 42).unwrap();
+    let result: i32 = con.get("my_key").unwrap();
+    assert_eq!(result, 42);
+}
+
 pub const IMAGE_WAITERS: [Image; 1] = [Image {
-    name: "bitnami/redis:6.2.13-debian-11-r73",
+    name: "bitnami/redis",
+    version: None,
     log_regex_to_wait_for: r"Ready to accept connections",
     timeout: Duration::from_secs(120),
 }];